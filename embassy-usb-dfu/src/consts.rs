@@ -0,0 +1,86 @@
+//! Constants and enums defined by the USB DFU 1.1 specification.
+#![allow(unused)]
+
+use bitflags::bitflags;
+
+pub(crate) const USB_CLASS_APPN_SPEC: u8 = 0xfe;
+pub(crate) const APPN_SPEC_SUBCLASS_DFU: u8 = 0x01;
+pub(crate) const DFU_PROTOCOL_RUNTIME: u8 = 0x01;
+pub(crate) const DFU_PROTOCOL_DFU: u8 = 0x02;
+
+/// DFU functional descriptor type, as defined by the DFU 1.1 spec.
+pub(crate) const DESC_DFU_FUNCTIONAL: u8 = 0x21;
+
+bitflags! {
+    /// Bitflags describing the capabilities of a DFU interface, reported in the DFU functional descriptor.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct DfuAttributes: u8 {
+        /// Device will perform a bus detach-attach sequence when it receives a DFU_DETACH request.
+        const WILL_DETACH = 0b0000_1000;
+        /// Device is able to communicate during the manifestation phase.
+        const MANIFESTATION_TOLERANT = 0b0000_0100;
+        /// Device is able to provide firmware upload.
+        const CAN_UPLOAD = 0b0000_0010;
+        /// Device is able to receive firmware download.
+        const CAN_DOWNLOAD = 0b0000_0001;
+        /// Non-standard, internal-only flag: the device understands the DfuSe (ST extension)
+        /// address-targeted DNLOAD commands. Never placed on the wire in the functional descriptor.
+        const DFUSE_COMMANDS = 0b0001_0000;
+    }
+}
+
+/// DFU state, as reported by GETSTATE/GETSTATUS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum State {
+    AppIdle = 0,
+    AppDetach = 1,
+    DfuIdle = 2,
+    DlSync = 3,
+    DlBusy = 4,
+    Download = 5,
+    ManifestSync = 6,
+    Manifest = 7,
+    UploadIdle = 9,
+    Error = 10,
+}
+
+/// DFU status, as reported by GETSTATUS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum Status {
+    Ok = 0x00,
+    ErrWrite = 0x03,
+    ErrVerify = 0x07,
+    ErrAddress = 0x08,
+    ErrUnknown = 0x0e,
+}
+
+/// DFU class-specific requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Request {
+    Detach,
+    Dnload,
+    Upload,
+    GetStatus,
+    ClrStatus,
+    GetState,
+    Abort,
+}
+
+impl TryFrom<u8> for Request {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Request::Detach),
+            1 => Ok(Request::Dnload),
+            2 => Ok(Request::Upload),
+            3 => Ok(Request::GetStatus),
+            4 => Ok(Request::ClrStatus),
+            5 => Ok(Request::GetState),
+            6 => Ok(Request::Abort),
+            _ => Err(()),
+        }
+    }
+}
@@ -2,7 +2,7 @@ use embassy_boot::{AlignedBuffer, BlockingFirmwareUpdater, FirmwareUpdaterError}
 use embassy_usb::control::{InResponse, OutResponse, Recipient, RequestType};
 use embassy_usb::driver::Driver;
 use embassy_usb::{Builder, Handler};
-use embedded_storage::nor_flash::{NorFlash, NorFlashErrorKind};
+use embedded_storage::nor_flash::{NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash};
 
 use crate::consts::{
     DfuAttributes, Request, State, Status, APPN_SPEC_SUBCLASS_DFU, DESC_DFU_FUNCTIONAL, DFU_PROTOCOL_DFU,
@@ -13,25 +13,50 @@ use crate::Reset;
 /// Internal state for USB DFU
 pub struct Control<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> {
     updater: BlockingFirmwareUpdater<'d, DFU, STATE>,
+    // `BlockingFirmwareUpdater` manages writes and manifestation but has no read-back API, so
+    // UPLOAD reads the DFU partition through this handle instead. See `Control::new`.
+    dfu: DFU,
     attrs: DfuAttributes,
     state: State,
     status: Status,
     offset: usize,
     buf: AlignedBuffer<BLOCK_SIZE>,
     reset: RST,
+    expected_block_num: u16,
+    poll_timeout_ms: u32,
+    address_pointer: u32,
 }
 
 impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> Control<'d, DFU, STATE, RST, BLOCK_SIZE> {
     /// Create a new DFU instance to handle DFU transfers.
-    pub fn new(updater: BlockingFirmwareUpdater<'d, DFU, STATE>, attrs: DfuAttributes, reset: RST) -> Self {
+    ///
+    /// `dfu` is used to read the DFU partition back out for UPLOAD, and must address the same
+    /// partition as `updater`. Most real flash peripherals can only be constructed once, so `dfu`
+    /// should normally be a [`crate::SharedFlash`] handle onto the same `Mutex<M, RefCell<_>>` the
+    /// caller also used to build `updater`'s `DFU`, rather than a second independent instance of
+    /// the underlying peripheral driver.
+    ///
+    /// `poll_timeout_ms` is reported to the host in GETSTATUS as the time it should wait before
+    /// polling again while the device is busy writing a block or manifesting the new firmware.
+    pub fn new(
+        updater: BlockingFirmwareUpdater<'d, DFU, STATE>,
+        dfu: DFU,
+        attrs: DfuAttributes,
+        reset: RST,
+        poll_timeout_ms: u32,
+    ) -> Self {
         Self {
             updater,
+            dfu,
             attrs,
             state: State::DfuIdle,
             status: Status::Ok,
             offset: 0,
             buf: AlignedBuffer([0; BLOCK_SIZE]),
             reset,
+            expected_block_num: 0,
+            poll_timeout_ms,
+            address_pointer: 0,
         }
     }
 
@@ -39,6 +64,66 @@ impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> Co
         self.offset = 0;
         self.state = State::DfuIdle;
         self.status = Status::Ok;
+        self.expected_block_num = 0;
+    }
+
+    /// Parse and execute a DfuSe (ST extension) command sent as the payload of a DNLOAD with
+    /// `wBlockNum == 0`. See AN3156 for the command encoding.
+    fn handle_dfuse_command(&mut self, data: &[u8]) -> OutResponse {
+        match data {
+            [0x21, a0, a1, a2, a3] => {
+                self.address_pointer = u32::from_le_bytes([*a0, *a1, *a2, *a3]);
+                debug!("DfuSe: set address pointer to {:#x}", self.address_pointer);
+                self.expected_block_num = 2;
+                OutResponse::Accepted
+            }
+            [0x41] => {
+                let capacity = self.dfu.capacity() as u32;
+                debug!("DfuSe: mass erase ({} bytes)", capacity);
+                match self.dfu.erase(0, capacity) {
+                    Ok(_) => OutResponse::Accepted,
+                    Err(e) => {
+                        error!("DfuSe: mass erase failed: {:?}", e);
+                        self.state = State::Error;
+                        self.status = FirmwareUpdaterError::Flash(e.kind()).into();
+                        OutResponse::Rejected
+                    }
+                }
+            }
+            [0x41, a0, a1, a2, a3] => {
+                let address = u32::from_le_bytes([*a0, *a1, *a2, *a3]);
+                let capacity = self.dfu.capacity() as u32;
+                let end = match address.checked_add(DFU::ERASE_SIZE as u32) {
+                    Some(end) if end <= capacity => end,
+                    _ => {
+                        error!("DfuSe: erase page at {:#x} is out of range (capacity {} bytes)", address, capacity);
+                        self.state = State::Error;
+                        self.status = Status::ErrAddress;
+                        return OutResponse::Rejected;
+                    }
+                };
+                debug!("DfuSe: erase page at {:#x}", address);
+                match self.dfu.erase(address, end) {
+                    Ok(_) => OutResponse::Accepted,
+                    Err(e) => {
+                        error!("DfuSe: erase at {:#x} failed: {:?}", address, e);
+                        self.state = State::Error;
+                        self.status = FirmwareUpdaterError::Flash(e.kind()).into();
+                        OutResponse::Rejected
+                    }
+                }
+            }
+            [0x92] => {
+                debug!("DfuSe: read unprotect (no-op, device has no readout protection)");
+                OutResponse::Accepted
+            }
+            _ => {
+                error!("DfuSe: unrecognized command {:?}", data);
+                self.state = State::Error;
+                self.status = Status::ErrUnknown;
+                OutResponse::Rejected
+            }
+        }
     }
 }
 
@@ -75,10 +160,16 @@ impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> Ha
                 Some(OutResponse::Accepted)
             }
             Ok(Request::Dnload) if self.attrs.contains(DfuAttributes::CAN_DOWNLOAD) => {
+                if req.value == 0 && self.attrs.contains(DfuAttributes::DFUSE_COMMANDS) && !data.is_empty() {
+                    self.state = State::Download;
+                    return Some(self.handle_dfuse_command(data));
+                }
+
                 if req.value == 0 {
                     info!("Download starting");
                     self.state = State::Download;
                     self.offset = 0;
+                    self.expected_block_num = 0;
                 }
 
                 if self.state != State::Download {
@@ -88,6 +179,17 @@ impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> Ha
                     return Some(OutResponse::Rejected);
                 }
 
+                // wBlockNum wraps at 16 bits; comparing as u16 handles that for free.
+                if req.value != self.expected_block_num {
+                    error!(
+                        "Unexpected block number {} (expected {}), dropping transfer",
+                        req.value, self.expected_block_num
+                    );
+                    self.status = Status::ErrUnknown;
+                    self.state = State::Error;
+                    return Some(OutResponse::Rejected);
+                }
+
                 if data.len() > BLOCK_SIZE {
                     error!("USB data len exceeded block size");
                     self.status = Status::ErrUnknown;
@@ -105,8 +207,8 @@ impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> Ha
                     match self.updater.mark_updated() {
                         Ok(_) => {
                             self.status = Status::Ok;
-                            self.state = State::ManifestSync;
-                            info!("Update complete");
+                            self.state = State::Manifest;
+                            info!("Update complete, entering manifestation phase");
                         }
                         Err(e) => {
                             error!("Error completing update: {}", e);
@@ -115,12 +217,19 @@ impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> Ha
                         }
                     }
                 } else {
-                    debug!("Writing {} bytes at {}", data.len(), self.offset);
-                    match self.updater.write_firmware(self.offset, self.buf.as_ref()) {
+                    let write_offset = if self.attrs.contains(DfuAttributes::DFUSE_COMMANDS) {
+                        self.address_pointer as usize + (req.value as usize - 2) * BLOCK_SIZE
+                    } else {
+                        self.offset
+                    };
+
+                    debug!("Writing {} bytes at {}", data.len(), write_offset);
+                    match self.updater.write_firmware(write_offset, self.buf.as_ref()) {
                         Ok(_) => {
                             self.status = Status::Ok;
-                            self.state = State::DlSync;
-                            self.offset += data.len();
+                            self.state = State::DlBusy;
+                            self.offset = write_offset + data.len();
+                            self.expected_block_num = self.expected_block_num.wrapping_add(1);
                         }
                         Err(e) => {
                             error!("Error writing firmware: {:?}", e);
@@ -153,14 +262,36 @@ impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> Ha
         }
         match Request::try_from(req.request) {
             Ok(Request::GetStatus) => {
+                // Report the state the device was in when polled, then advance it: this is what
+                // gives the host a dfuDNBUSY/dfuMANIFEST poll to wait out before the next request.
+                let mut reported_state = self.state;
                 match self.state {
-                    State::DlSync => self.state = State::Download,
-                    State::ManifestSync => self.reset.sys_reset(),
+                    State::DlBusy => self.state = State::Download,
+                    State::Manifest => self.state = State::ManifestSync,
+                    State::ManifestSync => {
+                        if self.attrs.contains(DfuAttributes::MANIFESTATION_TOLERANT) {
+                            info!("Manifestation-tolerant: returning to dfuIDLE without resetting");
+                            self.reset_state();
+                            // The host is polling to see the manifestation finish; report the
+                            // post-transition dfuIDLE now rather than making it poll once more.
+                            reported_state = self.state;
+                        } else {
+                            self.reset.sys_reset();
+                        }
+                    }
                     _ => {}
                 }
 
-                //TODO: Configurable poll timeout, ability to add string for Vendor error
-                buf[0..6].copy_from_slice(&[self.status as u8, 0x32, 0x00, 0x00, self.state as u8, 0x00]);
+                //TODO: ability to add string for Vendor error
+                let timeout = self.poll_timeout_ms.to_le_bytes();
+                buf[0..6].copy_from_slice(&[
+                    self.status as u8,
+                    timeout[0],
+                    timeout[1],
+                    timeout[2],
+                    reported_state as u8,
+                    0x00,
+                ]);
                 Some(InResponse::Accepted(&buf[0..6]))
             }
             Ok(Request::GetState) => {
@@ -168,8 +299,50 @@ impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> Ha
                 Some(InResponse::Accepted(&buf[0..1]))
             }
             Ok(Request::Upload) if self.attrs.contains(DfuAttributes::CAN_UPLOAD) => {
-                //TODO: FirmwareUpdater does not provide a way of reading the active partition, can't upload.
-                Some(InResponse::Rejected)
+                if req.value == 0 {
+                    info!("Upload starting");
+                    self.offset = 0;
+                    self.state = State::UploadIdle;
+                }
+
+                if self.state != State::UploadIdle {
+                    error!("Unexpected UPLOAD while chip is not idle");
+                    self.status = Status::ErrUnknown;
+                    self.state = State::Error;
+                    return Some(InResponse::Rejected);
+                }
+
+                // A block shorter than the full transfer size (including a zero-length one once
+                // the partition is exhausted) tells the host it has reached the end of upload.
+                let full_chunk = core::cmp::min(buf.len(), BLOCK_SIZE);
+                let remaining = self.dfu.capacity().saturating_sub(self.offset);
+                let chunk_size = core::cmp::min(full_chunk, remaining);
+
+                if chunk_size == 0 {
+                    info!("Upload complete");
+                    self.reset_state();
+                    return Some(InResponse::Accepted(&buf[..0]));
+                }
+
+                match self.dfu.read(self.offset as u32, &mut buf[..chunk_size]) {
+                    Ok(_) => {
+                        debug!("Read {} bytes from {}", chunk_size, self.offset);
+                        self.offset += chunk_size;
+
+                        if chunk_size < full_chunk {
+                            info!("Upload complete");
+                            self.reset_state();
+                        }
+
+                        Some(InResponse::Accepted(&buf[..chunk_size]))
+                    }
+                    Err(e) => {
+                        error!("Error reading firmware: {:?}", e);
+                        self.state = State::Error;
+                        self.status = FirmwareUpdaterError::Flash(e.kind()).into();
+                        Some(InResponse::Rejected)
+                    }
+                }
             }
             _ => None,
         }
@@ -179,10 +352,16 @@ impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> Ha
 /// An implementation of the USB DFU 1.1 protocol
 ///
 /// This function will add a DFU interface descriptor to the provided Builder, and register the provided Control as a handler for the USB device
-/// The handler is responsive to DFU GetState, GetStatus, Abort, and ClrStatus commands, as well as Download if configured by the user.
+/// The handler is responsive to DFU GetState, GetStatus, Abort, and ClrStatus commands, as well as Download and Upload if configured by the user.
 ///
 /// Once the host has initiated a DFU download operation, the chunks sent by the host will be written to the DFU partition.
-/// Once the final sync in the manifestation phase has been received, the handler will trigger a system reset to swap the new firmware.
+/// Once the final sync in the manifestation phase has been received, the handler will trigger a system reset to swap the
+/// new firmware — unless `DfuAttributes::MANIFESTATION_TOLERANT` is set, in which case it instead returns to `State::DfuIdle`
+/// without resetting, so the host can verify the new firmware before triggering a reset itself.
+///
+/// If `DfuAttributes::DFUSE_COMMANDS` is set, `wBlockNum == 0` DNLOAD payloads are instead parsed as
+/// DfuSe (ST extension) address-targeted erase/set-address-pointer commands, letting tools like
+/// `dfu-util -s` target an address within a larger flash region instead of writing from its start.
 pub fn usb_dfu<'d, D: Driver<'d>, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize>(
     builder: &mut Builder<'d, D>,
     handler: &'d mut Control<'d, DFU, STATE, RST, BLOCK_SIZE>,
@@ -193,7 +372,8 @@ pub fn usb_dfu<'d, D: Driver<'d>, DFU: NorFlash, STATE: NorFlash, RST: Reset, co
     alt.descriptor(
         DESC_DFU_FUNCTIONAL,
         &[
-            handler.attrs.bits(),
+            // DFUSE_COMMANDS is an internal-only bit, not part of the DFU 1.1 attributes byte.
+            (handler.attrs & !DfuAttributes::DFUSE_COMMANDS).bits(),
             0xc4,
             0x09, // 2500ms timeout, doesn't affect operation as DETACH not necessary in bootloader code
             (BLOCK_SIZE & 0xff) as u8,
@@ -206,3 +386,338 @@ pub fn usb_dfu<'d, D: Driver<'d>, DFU: NorFlash, STATE: NorFlash, RST: Reset, co
     drop(func);
     builder.handler(handler);
 }
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+
+    use embassy_boot::FirmwareUpdaterConfig;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embassy_sync::blocking_mutex::Mutex;
+    use embedded_storage::nor_flash::{ErrorType, NorFlashError, NorFlashErrorKind, ReadNorFlash};
+    use std::boxed::Box;
+
+    use super::*;
+    use crate::SharedFlash;
+
+    const PAGE_SIZE: usize = 256;
+    const BLOCK_SIZE: usize = 64;
+
+    struct MockFlash([u8; PAGE_SIZE * 4]);
+
+    impl Default for MockFlash {
+        fn default() -> Self {
+            Self([0xff; PAGE_SIZE * 4])
+        }
+    }
+
+    #[derive(Debug)]
+    struct MockError;
+
+    impl NorFlashError for MockError {
+        fn kind(&self) -> NorFlashErrorKind {
+            NorFlashErrorKind::Other
+        }
+    }
+
+    impl ErrorType for MockFlash {
+        type Error = MockError;
+    }
+
+    impl ReadNorFlash for MockFlash {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            bytes.copy_from_slice(&self.0[offset..offset + bytes.len()]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    impl NorFlash for MockFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = PAGE_SIZE;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            self.0[from as usize..to as usize].fill(0xff);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            self.0[offset..offset + bytes.len()].copy_from_slice(bytes);
+            Ok(())
+        }
+    }
+
+    struct MockReset;
+
+    impl Reset for MockReset {
+        fn sys_reset(&mut self) -> ! {
+            panic!("unexpected reset")
+        }
+    }
+
+    type SharedMockFlash = SharedFlash<'static, NoopRawMutex, MockFlash>;
+    type MockControl = Control<'static, SharedMockFlash, MockFlash, MockReset, BLOCK_SIZE>;
+
+    // `updater` and `Control` share one underlying `MockFlash` through this mutex, mirroring how
+    // a real flash peripheral can only be constructed once (see `SharedFlash`'s doc comment).
+    fn control_with_shared_flash(attrs: DfuAttributes) -> (MockControl, &'static Mutex<NoopRawMutex, RefCell<MockFlash>>) {
+        let flash: &'static _ = Box::leak(Box::new(Mutex::new(RefCell::new(MockFlash::default()))));
+        let config = FirmwareUpdaterConfig {
+            dfu: SharedFlash::new(flash),
+            state: MockFlash::default(),
+        };
+        let updater = BlockingFirmwareUpdater::new(config, Box::leak(Box::new([0xff; PAGE_SIZE])));
+        let control = Control::new(updater, SharedFlash::new(flash), attrs, MockReset, 50);
+        (control, flash)
+    }
+
+    fn control_with_attrs(attrs: DfuAttributes) -> MockControl {
+        control_with_shared_flash(attrs).0
+    }
+
+    fn control() -> MockControl {
+        control_with_attrs(DfuAttributes::CAN_DOWNLOAD)
+    }
+
+    fn dnload(c: &mut MockControl, block_num: u16, data: &[u8]) -> OutResponse {
+        let req = embassy_usb::control::Request {
+            request_type: RequestType::Class,
+            recipient: Recipient::Interface,
+            request: Request::Dnload as u8,
+            value: block_num,
+            index: 0,
+            length: data.len() as u16,
+        };
+        c.control_out(req, data).expect("DNLOAD should be handled")
+    }
+
+    #[test]
+    fn accepts_in_order_blocks() {
+        let mut c = control();
+        assert_eq!(dnload(&mut c, 0, &[0xaa; 16]), OutResponse::Accepted);
+        get_status(&mut c); // advances DlBusy -> Download
+        assert_eq!(dnload(&mut c, 1, &[0xbb; 16]), OutResponse::Accepted);
+        assert_eq!(c.state, State::DlBusy);
+        assert_eq!(c.status, Status::Ok);
+    }
+
+    #[test]
+    fn rejects_skipped_block() {
+        let mut c = control();
+        assert_eq!(dnload(&mut c, 0, &[0xaa; 16]), OutResponse::Accepted);
+        get_status(&mut c); // advances DlBusy -> Download
+        assert_eq!(dnload(&mut c, 2, &[0xbb; 16]), OutResponse::Rejected);
+        assert_eq!(c.state, State::Error);
+        assert_eq!(c.status, Status::ErrUnknown);
+    }
+
+    #[test]
+    fn rejects_duplicate_block() {
+        let mut c = control();
+        assert_eq!(dnload(&mut c, 0, &[0xaa; 16]), OutResponse::Accepted);
+        get_status(&mut c); // advances DlBusy -> Download
+        assert_eq!(dnload(&mut c, 1, &[0xbb; 16]), OutResponse::Accepted);
+        get_status(&mut c);
+        assert_eq!(dnload(&mut c, 1, &[0xcc; 16]), OutResponse::Rejected);
+        assert_eq!(c.state, State::Error);
+        assert_eq!(c.status, Status::ErrUnknown);
+    }
+
+    fn upload<'b>(
+        c: &'b mut MockControl,
+        block_num: u16,
+        buf: &'b mut [u8],
+    ) -> InResponse<'b> {
+        let req = embassy_usb::control::Request {
+            request_type: RequestType::Class,
+            recipient: Recipient::Interface,
+            request: Request::Upload as u8,
+            value: block_num,
+            index: 0,
+            length: buf.len() as u16,
+        };
+        c.control_in(req, buf).expect("UPLOAD should be handled")
+    }
+
+    #[test]
+    fn upload_reads_whole_partition_then_terminates() {
+        let mut c = control_with_attrs(DfuAttributes::CAN_UPLOAD);
+        let mut buf = [0u8; BLOCK_SIZE];
+        let mut block_num = 0;
+        let mut total = 0;
+        loop {
+            match upload(&mut c, block_num, &mut buf) {
+                InResponse::Accepted(data) if !data.is_empty() => {
+                    total += data.len();
+                    block_num += 1;
+                }
+                InResponse::Accepted(_) => break,
+                InResponse::Rejected => panic!("upload rejected"),
+            }
+        }
+        assert_eq!(total, PAGE_SIZE * 4);
+        assert_eq!(c.state, State::DfuIdle);
+        assert_eq!(c.status, Status::Ok);
+    }
+
+    #[test]
+    fn upload_rejected_when_not_idle() {
+        let mut c = control_with_attrs(DfuAttributes::CAN_UPLOAD | DfuAttributes::CAN_DOWNLOAD);
+        assert_eq!(dnload(&mut c, 0, &[0xaa; 16]), OutResponse::Accepted);
+
+        let mut buf = [0u8; BLOCK_SIZE];
+        let req = embassy_usb::control::Request {
+            request_type: RequestType::Class,
+            recipient: Recipient::Interface,
+            request: Request::Upload as u8,
+            value: 1,
+            index: 0,
+            length: buf.len() as u16,
+        };
+        assert!(matches!(c.control_in(req, &mut buf), Some(InResponse::Rejected)));
+        assert_eq!(c.state, State::Error);
+    }
+
+    fn get_status(c: &mut MockControl) -> u8 {
+        let req = embassy_usb::control::Request {
+            request_type: RequestType::Class,
+            recipient: Recipient::Interface,
+            request: Request::GetStatus as u8,
+            value: 0,
+            index: 0,
+            length: 6,
+        };
+        let mut buf = [0u8; 6];
+        match c.control_in(req, &mut buf) {
+            Some(InResponse::Accepted(data)) => data[4],
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn dlbusy_and_manifest_are_one_shot_pacing_states() {
+        let mut c = control();
+        assert_eq!(dnload(&mut c, 0, &[0xaa; 16]), OutResponse::Accepted);
+        assert_eq!(c.state, State::DlBusy);
+
+        assert_eq!(get_status(&mut c), State::DlBusy as u8);
+        assert_eq!(c.state, State::Download);
+
+        // Final (zero-length) transfer ends the download and moves to dfuMANIFEST.
+        assert_eq!(dnload(&mut c, 1, &[]), OutResponse::Accepted);
+        assert_eq!(c.state, State::Manifest);
+
+        assert_eq!(get_status(&mut c), State::Manifest as u8);
+        assert_eq!(c.state, State::ManifestSync);
+    }
+
+    #[test]
+    fn manifestation_tolerant_getstatus_reports_idle_immediately() {
+        let mut c = control_with_attrs(DfuAttributes::CAN_DOWNLOAD | DfuAttributes::MANIFESTATION_TOLERANT);
+        assert_eq!(dnload(&mut c, 0, &[]), OutResponse::Accepted);
+        assert_eq!(c.state, State::Manifest);
+        assert_eq!(get_status(&mut c), State::Manifest as u8);
+        assert_eq!(c.state, State::ManifestSync);
+
+        // The host's next poll should see dfuIDLE right away, not ManifestSync again.
+        assert_eq!(get_status(&mut c), State::DfuIdle as u8);
+        assert_eq!(c.state, State::DfuIdle);
+    }
+
+    fn dfuse_command(c: &mut MockControl, data: &[u8]) -> OutResponse {
+        dnload(c, 0, data)
+    }
+
+    #[test]
+    fn dfuse_set_address_pointer() {
+        let mut c = control_with_attrs(DfuAttributes::CAN_DOWNLOAD | DfuAttributes::DFUSE_COMMANDS);
+        assert_eq!(dfuse_command(&mut c, &[0x21, 0x00, 0x04, 0x00, 0x08]), OutResponse::Accepted);
+        assert_eq!(c.address_pointer, 0x0800_0400);
+        assert_eq!(c.expected_block_num, 2);
+    }
+
+    #[test]
+    fn dfuse_page_erase() {
+        let (mut c, flash) = control_with_shared_flash(DfuAttributes::CAN_DOWNLOAD | DfuAttributes::DFUSE_COMMANDS);
+        flash.lock(|f| f.borrow_mut().0[0..PAGE_SIZE].fill(0x42));
+        assert_eq!(dfuse_command(&mut c, &[0x41, 0x00, 0x00, 0x00, 0x00]), OutResponse::Accepted);
+        flash.lock(|f| {
+            let f = f.borrow();
+            assert!(f.0[0..PAGE_SIZE].iter().all(|b| *b == 0xff));
+            assert!(f.0[PAGE_SIZE..].iter().all(|b| *b == 0x42));
+        });
+    }
+
+    #[test]
+    fn dfuse_mass_erase_clears_whole_partition() {
+        let (mut c, flash) = control_with_shared_flash(DfuAttributes::CAN_DOWNLOAD | DfuAttributes::DFUSE_COMMANDS);
+        flash.lock(|f| f.borrow_mut().0.fill(0x42));
+        assert_eq!(dfuse_command(&mut c, &[0x41]), OutResponse::Accepted);
+        flash.lock(|f| assert!(f.borrow().0.iter().all(|b| *b == 0xff)));
+    }
+
+    #[test]
+    fn dfuse_page_erase_rejects_out_of_range_address() {
+        // MockFlash's capacity is PAGE_SIZE * 4; a page starting at that offset doesn't fit.
+        let mut c = control_with_attrs(DfuAttributes::CAN_DOWNLOAD | DfuAttributes::DFUSE_COMMANDS);
+        let address = (PAGE_SIZE * 4) as u32;
+        assert_eq!(
+            dfuse_command(&mut c, &[0x41, address as u8, (address >> 8) as u8, (address >> 16) as u8, (address >> 24) as u8]),
+            OutResponse::Rejected
+        );
+        assert_eq!(c.state, State::Error);
+        assert_eq!(c.status, Status::ErrAddress);
+    }
+
+    #[test]
+    fn dfuse_page_erase_rejects_address_that_would_overflow() {
+        let mut c = control_with_attrs(DfuAttributes::CAN_DOWNLOAD | DfuAttributes::DFUSE_COMMANDS);
+        let address = u32::MAX - 10;
+        assert_eq!(
+            dfuse_command(&mut c, &[0x41, address as u8, (address >> 8) as u8, (address >> 16) as u8, (address >> 24) as u8]),
+            OutResponse::Rejected
+        );
+        assert_eq!(c.state, State::Error);
+        assert_eq!(c.status, Status::ErrAddress);
+    }
+
+    #[test]
+    fn download_then_upload_round_trips_through_shared_flash() {
+        let (mut c, _flash) = control_with_shared_flash(DfuAttributes::CAN_DOWNLOAD | DfuAttributes::CAN_UPLOAD);
+        assert_eq!(dnload(&mut c, 0, &[0xaa; 16]), OutResponse::Accepted);
+        assert_eq!(c.state, State::DlBusy);
+        get_status(&mut c); // advances DlBusy -> Download
+        assert_eq!(dnload(&mut c, 1, &[]), OutResponse::Accepted); // final transfer
+        assert_eq!(c.state, State::Manifest);
+
+        // UPLOAD reads back through `Control`'s own `dfu` handle; since it shares the same
+        // underlying flash as `updater` (see `SharedFlash`), the bytes just written are visible.
+        let mut buf = [0u8; BLOCK_SIZE];
+        match upload(&mut c, 0, &mut buf) {
+            InResponse::Accepted(data) => assert_eq!(&data[..16], &[0xaa; 16]),
+            InResponse::Rejected => panic!("upload rejected"),
+        }
+    }
+
+    #[test]
+    fn dfuse_read_unprotect_is_accepted_noop() {
+        let mut c = control_with_attrs(DfuAttributes::CAN_DOWNLOAD | DfuAttributes::DFUSE_COMMANDS);
+        assert_eq!(dfuse_command(&mut c, &[0x92]), OutResponse::Accepted);
+        assert_eq!(c.state, State::Download);
+    }
+
+    #[test]
+    fn dfuse_unknown_command_errors() {
+        let mut c = control_with_attrs(DfuAttributes::CAN_DOWNLOAD | DfuAttributes::DFUSE_COMMANDS);
+        assert_eq!(dfuse_command(&mut c, &[0xaa]), OutResponse::Rejected);
+        assert_eq!(c.state, State::Error);
+        assert_eq!(c.status, Status::ErrUnknown);
+    }
+}
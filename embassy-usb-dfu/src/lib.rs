@@ -0,0 +1,21 @@
+#![no_std]
+#![allow(async_fn_in_trait)]
+
+#[cfg(test)]
+extern crate std;
+
+mod consts;
+mod dfu;
+mod shared_flash;
+mod usb_dfu_runtime;
+
+pub use consts::DfuAttributes;
+pub use dfu::{usb_dfu, Control};
+pub use shared_flash::SharedFlash;
+pub use usb_dfu_runtime::{usb_dfu_runtime, RuntimeControl};
+
+/// An interface to reset the device into or out of a bootloader mode.
+pub trait Reset {
+    /// Resets the device.
+    fn sys_reset(&mut self) -> !;
+}
@@ -0,0 +1,60 @@
+//! A `NorFlash` wrapper that lets more than one owner share a single flash peripheral.
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embedded_storage::nor_flash::{ErrorType, NorFlash, ReadNorFlash};
+
+/// A handle to a `NorFlash` peripheral shared behind a blocking mutex.
+///
+/// Real flash peripherals (an internal flash controller, a DMA-driven QSPI/OSPI flash, ...) can
+/// usually only be constructed once. `SharedFlash` lets [`crate::Control`] read the DFU
+/// partition back for UPLOAD through the same underlying peripheral that `BlockingFirmwareUpdater`
+/// writes through, rather than requiring the caller to own the device twice. Construct one
+/// `Mutex<M, RefCell<DFU>>` around the real flash and hand out a `SharedFlash` (cheaply `Copy`)
+/// to each of `BlockingFirmwareUpdater` and [`crate::Control::new`].
+pub struct SharedFlash<'a, M: RawMutex, DFU>(&'a Mutex<M, RefCell<DFU>>);
+
+impl<'a, M: RawMutex, DFU> SharedFlash<'a, M, DFU> {
+    /// Create a new handle onto a flash peripheral shared via `flash`.
+    pub fn new(flash: &'a Mutex<M, RefCell<DFU>>) -> Self {
+        Self(flash)
+    }
+}
+
+impl<'a, M: RawMutex, DFU> Clone for SharedFlash<'a, M, DFU> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, M: RawMutex, DFU> Copy for SharedFlash<'a, M, DFU> {}
+
+impl<'a, M: RawMutex, DFU: ErrorType> ErrorType for SharedFlash<'a, M, DFU> {
+    type Error = DFU::Error;
+}
+
+impl<'a, M: RawMutex, DFU: ReadNorFlash> ReadNorFlash for SharedFlash<'a, M, DFU> {
+    const READ_SIZE: usize = DFU::READ_SIZE;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.0.lock(|flash| flash.borrow_mut().read(offset, bytes))
+    }
+
+    fn capacity(&self) -> usize {
+        self.0.lock(|flash| flash.borrow().capacity())
+    }
+}
+
+impl<'a, M: RawMutex, DFU: NorFlash> NorFlash for SharedFlash<'a, M, DFU> {
+    const WRITE_SIZE: usize = DFU::WRITE_SIZE;
+    const ERASE_SIZE: usize = DFU::ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.0.lock(|flash| flash.borrow_mut().erase(from, to))
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.0.lock(|flash| flash.borrow_mut().write(offset, bytes))
+    }
+}
@@ -0,0 +1,206 @@
+use embassy_usb::control::{InResponse, OutResponse, Recipient, RequestType};
+use embassy_usb::driver::Driver;
+use embassy_usb::{Builder, Handler};
+
+use crate::consts::{
+    Request, State, Status, APPN_SPEC_SUBCLASS_DFU, DESC_DFU_FUNCTIONAL, DFU_PROTOCOL_RUNTIME, USB_CLASS_APPN_SPEC,
+};
+use crate::{DfuAttributes, Reset};
+
+/// Internal state for the runtime-mode USB DFU handler.
+pub struct RuntimeControl<RST: Reset> {
+    attrs: DfuAttributes,
+    timeout_ms: u16,
+    reset: RST,
+    detach_requested: bool,
+}
+
+impl<RST: Reset> RuntimeControl<RST> {
+    /// Create a new runtime DFU instance, advertising `timeout_ms` as the time the host should
+    /// wait after a DETACH before expecting the device to reset into the DFU-mode bootloader.
+    pub fn new(reset: RST, attrs: DfuAttributes, timeout_ms: u16) -> Self {
+        Self {
+            attrs,
+            timeout_ms,
+            reset,
+            detach_requested: false,
+        }
+    }
+
+    fn state(&self) -> State {
+        if self.detach_requested {
+            State::AppDetach
+        } else {
+            State::AppIdle
+        }
+    }
+
+    /// True once a DETACH has been received and the device is waiting out `timeout_ms` before
+    /// resetting into the DFU-mode bootloader.
+    pub fn detach_requested(&self) -> bool {
+        self.detach_requested
+    }
+
+    /// Reset into the DFU-mode bootloader registered by [`crate::usb_dfu`].
+    ///
+    /// Call this once `timeout_ms` has elapsed after [`Self::detach_requested`] first returns
+    /// true, e.g. from a task that does `Timer::after_millis(timeout_ms).await` before calling
+    /// this. `RuntimeControl` has no timer of its own, since `control_out` must return
+    /// synchronously, so honoring `wDetachTimeOut` is left to the caller.
+    pub fn trigger_reset(&mut self) -> ! {
+        self.reset.sys_reset()
+    }
+}
+
+impl<RST: Reset> Handler for RuntimeControl<RST> {
+    fn control_out(
+        &mut self,
+        req: embassy_usb::control::Request,
+        _data: &[u8],
+    ) -> Option<embassy_usb::control::OutResponse> {
+        if (req.request_type, req.recipient) != (RequestType::Class, Recipient::Interface) {
+            debug!("Unknown out request: {:?}", req);
+            return None;
+        }
+        match Request::try_from(req.request) {
+            Ok(Request::Detach) => {
+                info!("Detach requested, arming reset into DFU mode");
+                self.detach_requested = true;
+                Some(OutResponse::Accepted)
+            }
+            _ => None,
+        }
+    }
+
+    fn control_in<'a>(
+        &'a mut self,
+        req: embassy_usb::control::Request,
+        buf: &'a mut [u8],
+    ) -> Option<embassy_usb::control::InResponse<'a>> {
+        if (req.request_type, req.recipient) != (RequestType::Class, Recipient::Interface) {
+            debug!("Unknown in request: {:?}", req);
+            return None;
+        }
+        match Request::try_from(req.request) {
+            Ok(Request::GetStatus) => {
+                // bwPollTimeOut is a separate wire field from wDetachTimeOut (the functional
+                // descriptor's `timeout_ms`): it paces dfuDNBUSY/dfuMANIFEST polling, neither of
+                // which applies in runtime mode, so there's no delay to report here.
+                buf[0..6].copy_from_slice(&[Status::Ok as u8, 0x00, 0x00, 0x00, self.state() as u8, 0x00]);
+                Some(InResponse::Accepted(&buf[0..6]))
+            }
+            Ok(Request::GetState) => {
+                buf[0] = self.state() as u8;
+                Some(InResponse::Accepted(&buf[0..1]))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// An implementation of the runtime side of the USB DFU 1.1 protocol.
+///
+/// This registers a DFU interface advertising `DFU_PROTOCOL_RUNTIME` so a normally-running
+/// application can tell a host it supports DFU without being in the bootloader. On receiving
+/// a DETACH request the handler arms [`RuntimeControl::detach_requested`]; the caller is
+/// responsible for resetting into the bootloader-mode DFU interface registered by
+/// [`crate::usb_dfu`] once `wDetachTimeOut` has elapsed, via [`RuntimeControl::trigger_reset`].
+pub fn usb_dfu_runtime<'d, D: Driver<'d>, RST: Reset>(
+    builder: &mut Builder<'d, D>,
+    handler: &'d mut RuntimeControl<RST>,
+) {
+    let mut func = builder.function(USB_CLASS_APPN_SPEC, APPN_SPEC_SUBCLASS_DFU, DFU_PROTOCOL_RUNTIME);
+    let mut iface = func.interface();
+    let mut alt = iface.alt_setting(USB_CLASS_APPN_SPEC, APPN_SPEC_SUBCLASS_DFU, DFU_PROTOCOL_RUNTIME, None);
+    let timeout = handler.timeout_ms.to_le_bytes();
+    alt.descriptor(
+        DESC_DFU_FUNCTIONAL,
+        &[
+            // Mask out DFUSE_COMMANDS: it's an internal-only flag describing DfuSe DNLOAD
+            // command support and must never reach the wire, same as in `usb_dfu`.
+            (handler.attrs & !DfuAttributes::DFUSE_COMMANDS).bits(),
+            timeout[0],
+            timeout[1],
+            0x00, // wTransferSize: no transfers happen in runtime mode
+            0x00,
+            0x10,
+            0x01, // DFU 1.1
+        ],
+    );
+
+    drop(func);
+    builder.handler(handler);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockReset;
+
+    impl Reset for MockReset {
+        fn sys_reset(&mut self) -> ! {
+            panic!("reset triggered")
+        }
+    }
+
+    fn detach(c: &mut RuntimeControl<MockReset>) -> OutResponse {
+        let req = embassy_usb::control::Request {
+            request_type: RequestType::Class,
+            recipient: Recipient::Interface,
+            request: Request::Detach as u8,
+            value: 0,
+            index: 0,
+            length: 0,
+        };
+        c.control_out(req, &[]).expect("DETACH should be handled")
+    }
+
+    #[test]
+    fn detach_arms_without_resetting() {
+        let mut c = RuntimeControl::new(MockReset, DfuAttributes::empty(), 500);
+        assert!(!c.detach_requested());
+
+        assert_eq!(detach(&mut c), OutResponse::Accepted);
+        assert!(c.detach_requested());
+        assert_eq!(c.state(), State::AppDetach);
+    }
+
+    #[test]
+    fn get_status_reports_app_idle_before_detach() {
+        let mut c = RuntimeControl::new(MockReset, DfuAttributes::empty(), 500);
+        let mut buf = [0u8; 6];
+        let req = embassy_usb::control::Request {
+            request_type: RequestType::Class,
+            recipient: Recipient::Interface,
+            request: Request::GetStatus as u8,
+            value: 0,
+            index: 0,
+            length: 6,
+        };
+        match c.control_in(req, &mut buf) {
+            Some(InResponse::Accepted(data)) => assert_eq!(data[4], State::AppIdle as u8),
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn get_status_reports_zero_poll_timeout_regardless_of_detach_timeout() {
+        // bwPollTimeOut (bytes 1..=3) must stay independent of wDetachTimeOut (`timeout_ms`,
+        // only used in the functional descriptor), however large the latter is.
+        let mut c = RuntimeControl::new(MockReset, DfuAttributes::empty(), 5000);
+        let mut buf = [0u8; 6];
+        let req = embassy_usb::control::Request {
+            request_type: RequestType::Class,
+            recipient: Recipient::Interface,
+            request: Request::GetStatus as u8,
+            value: 0,
+            index: 0,
+            length: 6,
+        };
+        match c.control_in(req, &mut buf) {
+            Some(InResponse::Accepted(data)) => assert_eq!(&data[1..4], &[0x00, 0x00, 0x00]),
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+}